@@ -1,21 +1,116 @@
+use std::env;
+use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::IsTerminal;
+use std::io::Read;
 use std::io::StdinLock;
 use std::io::StdoutLock;
 use std::io::Write;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::Duration;
 
+use clap::builder::FalseyValueParser;
 use clap::ValueEnum;
 use clap::{Args, Parser, Subcommand};
+use owo_colors::OwoColorize;
+use owo_colors::Stream;
 use resistance_civil_protection::email;
 use resistance_civil_protection::CivilProtection;
+use serde_json::json;
 use syslog::BasicLogger;
 use syslog::Facility;
 use syslog::Formatter3164;
 
+/// Environment variable holding the email password for non-interactive setup
+const PASSWORD_ENV_VAR: &str = "CMACM_EMAIL_PASSWORD";
+
+/// Environment variable holding the default `--max-recipients` cap
+const MAX_RECIPIENTS_ENV_VAR: &str = "CMACM_MAX_RECIPIENTS";
+
+/// Name of the systemd credential (under `$CREDENTIALS_DIRECTORY`) holding the email password
+const PASSWORD_CREDENTIAL_NAME: &str = "cmacm-email-password";
+
+/// Comma-separated list of domains the from-address must belong to, set at build time.
+/// When unset or empty, any domain is allowed.
+const ALLOWED_FROM_DOMAINS: Option<&str> = option_env!("CMACM_ALLOWED_FROM_DOMAINS");
+
+/// Squadmate changes the running command has already written, so a `--deadline` abort can report them
+struct Progress {
+    removed: AtomicUsize,
+    added: AtomicUsize,
+    skipped: AtomicUsize,
+}
+
+impl Progress {
+    fn summary(&self) -> Option<String> {
+        let removed = self.removed.load(Ordering::SeqCst);
+        let added = self.added.load(Ordering::SeqCst);
+        let skipped = self.skipped.load(Ordering::SeqCst);
+        if removed + added + skipped == 0 {
+            return None;
+        }
+        Some(format!("removed {}, added {} and skipped {} squadmates", removed, added, skipped))
+    }
+}
+
+static PROGRESS: Progress = Progress {
+    removed: AtomicUsize::new(0),
+    added: AtomicUsize::new(0),
+    skipped: AtomicUsize::new(0),
+};
+
+macro_rules! print_success {
+    ($($arg:tt)*) => {
+        println!("{} {}", "✓".if_supports_color(Stream::Stdout, |s| s.green()), format_args!($($arg)*))
+    };
+}
+
+macro_rules! print_warning {
+    ($($arg:tt)*) => {
+        println!("{} {}", "⚠".if_supports_color(Stream::Stdout, |s| s.yellow()), format_args!($($arg)*))
+    };
+}
+
+macro_rules! print_failure {
+    ($($arg:tt)*) => {
+        println!("{} {}", "✗".if_supports_color(Stream::Stdout, |s| s.red()), format_args!($($arg)*))
+    };
+}
+
+macro_rules! print_error {
+    ($($arg:tt)*) => {
+        eprintln!("{} {}", "✗".if_supports_color(Stream::Stderr, |s| s.red()), format_args!($($arg)*))
+    };
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Don't send log messages to syslog. CMACM_NO_SYSLOG is off when empty or one of
+    /// `0`, `false`, `f`, `no`, `n` or `off`, and on for any other value
+    #[arg(long, global = true, env = "CMACM_NO_SYSLOG", value_parser = FalseyValueParser::new())]
+    no_syslog: bool,
+
+    /// Abort with an error if the command takes longer than this (e.g. "30s", "5m")
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    deadline: Option<Duration>,
+
+    /// Disable colored output (also disabled by setting NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+
     #[command(subcommand)]
     squadmate_cmd: Option<SquadmateCommands>,
 }
@@ -25,13 +120,35 @@ enum SquadmateCommands {
     Setup(SquadmateSetupArgs),
     Add(SquadmateAddArgs),
     Remove(SquadmateRmArgs),
-    Test,
+    Test(SquadmateTestArgs),
+    List(SquadmateListArgs),
+    Import(SquadmateImportArgs),
+    Export(SquadmateExportArgs),
+    Doctor(SquadmateDoctorArgs),
 }
 
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Default)]
 struct SquadmateSetupArgs {
     #[arg(value_enum)]
     email_method: Option<SquadmateSetupEmailMethod>,
+
+    /// Human-readable name to show to recipients, instead of prompting
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Email address to send emails from, instead of prompting
+    #[arg(long, value_parser = parse_email)]
+    from: Option<String>,
+
+    /// Read the SMTP password from this inherited file descriptor instead of prompting.
+    /// Without it, the password is taken from $CMACM_EMAIL_PASSWORD, then from the
+    /// `cmacm-email-password` systemd credential in $CREDENTIALS_DIRECTORY, then prompted for
+    #[arg(long)]
+    password_fd: Option<u32>,
+
+    /// Reinitialize an existing setup without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -42,8 +159,10 @@ enum SquadmateSetupEmailMethod {
 
 #[derive(Args, Debug)]
 struct SquadmateAddArgs {
+    /// The squadmate's name, or both name and email as "John Doe <johndoe@example.com>"
     name: String,
-    email: String,
+    #[arg(value_parser = parse_email)]
+    email: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -51,6 +170,10 @@ struct SquadmateRmArgs {
     #[arg(value_enum)]
     field_type: SquadmateRmFieldType,
     value: String,
+
+    /// Remove without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -59,9 +182,116 @@ enum SquadmateRmFieldType {
     Name,
 }
 
+#[derive(Args, Debug)]
+struct SquadmateTestArgs {
+    /// Print the result as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Refuse to send if more than this many squadmates would be notified
+    #[arg(long, env = MAX_RECIPIENTS_ENV_VAR)]
+    max_recipients: Option<usize>,
+
+    /// Send even if the number of squadmates exceeds `--max-recipients`
+    #[arg(long)]
+    force: bool,
+
+    /// Describe what would be sent, and to whom, without sending anything
+    #[arg(long, conflicts_with = "json")]
+    explain: bool,
+
+    /// Log in and resolve recipients, but don't send anything
+    #[arg(long, conflicts_with = "explain")]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug, Default)]
+struct SquadmateListArgs {
+    #[arg(long, value_enum, default_value_t)]
+    format: SquadmateListFormat,
+
+    /// Print only each squadmate's email address, one per line
+    #[arg(long, conflicts_with_all = ["format", "name_only"])]
+    email_only: bool,
+
+    /// Print only each squadmate's name, one per line
+    #[arg(long, conflicts_with = "format")]
+    name_only: bool,
+
+    /// Show at most this many squadmates
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Skip this many squadmates before listing
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum SquadmateListFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum SquadmateFileFormat {
+    /// `name,email` rows, with an optional header row
+    #[default]
+    Csv,
+    /// One vCard per squadmate, using its FN and EMAIL
+    Vcard,
+}
+
+#[derive(Args, Debug)]
+struct SquadmateImportArgs {
+    /// File of squadmates to import. For vCard files, cards without a usable email are skipped
+    path: PathBuf,
+
+    /// Format of the file
+    #[arg(long, value_enum, default_value_t)]
+    format: SquadmateFileFormat,
+
+    /// Remove every existing squadmate before importing, instead of appending.
+    /// By default rows whose email is already a squadmate are skipped; with this,
+    /// only repeats within the file are skipped
+    #[arg(long)]
+    replace: bool,
+
+    /// Print the squadmates that would be added, skipped and removed, then ask before changing anything
+    #[arg(long)]
+    diff: bool,
+
+    /// Don't ask for confirmation before `--replace` or after `--diff`
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct SquadmateExportArgs {
+    /// File to write the squadmates to
+    path: PathBuf,
+
+    /// Format of the file
+    #[arg(long, value_enum, default_value_t)]
+    format: SquadmateFileFormat,
+}
+
+#[derive(Args, Debug)]
+struct SquadmateDoctorArgs {
+    /// Print nothing and report only through the exit code: 0 if every check passes,
+    /// otherwise a code identifying the first check that failed
+    #[arg(long)]
+    check: bool,
+
+    /// With `--check`, print a single JSON verdict
+    #[arg(long, requires = "check")]
+    json: bool,
+}
+
 fn check_config(cp: &CivilProtection) {
     if !cp.does_config_exist() {
-        eprintln!("Resistance is not setup yet!");
+        print_error!("Resistance is not setup yet!");
         process::exit(1);
     }
 }
@@ -86,13 +316,21 @@ fn setup_logging() {
     let _ = log::set_boxed_logger(syslog_box).map(|()| log::set_max_level(log::LevelFilter::Info));
 }
 
-fn prompt_yn(prompt: &str, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> bool {
+fn prompt_yn(prompt: &str, assume_yes: bool, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> bool {
+    if assume_yes {
+        return true
+    }
+    if !stdin.is_terminal() {
+        print_error!("Standard input is not a terminal, pass `--yes` to confirm non-interactively");
+        process::exit(1);
+    }
+
     let mut buf = String::new();
     print!("{} (y/N): ", prompt);
     stdout.flush().unwrap();
 
     stdin.read_line(&mut buf).unwrap_or_else(|e| {
-        eprintln!("Failed to read from standard input: {}", e);
+        print_error!("Failed to read from standard input: {}", e);
         process::exit(1);
     });
 
@@ -102,39 +340,71 @@ fn prompt_yn(prompt: &str, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> bo
     true
 }
 
-fn cmd_setup_check(cp: &mut CivilProtection, stdout: &mut StdoutLock, stdin: &mut StdinLock) {
-    if cp.does_config_exist() {
-        let response_yes = prompt_yn("Resistance is already setup! Are you sure you want to reinitialize setup?", stdout, stdin);
-        if response_yes {
-            cp.delete_config().unwrap_or_else(|e| {
-                eprintln!("Failed to delete existing config: {}", e);
-                process::exit(1);
-            });
-        } else {
-            println!("Canceled");
-            process::exit(1);
-        }
+/// Asks before reinitializing an existing setup, returning whether there is a config to replace.
+/// The config is only deleted by `cmd_setup_delete_config` once every setup value has been
+/// gathered, so a missing or invalid value can't leave us without one.
+fn cmd_setup_check(cp: &mut CivilProtection, assume_yes: bool, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> bool {
+    if !cp.does_config_exist() {
+        return false
+    }
+
+    let response_yes = prompt_yn("Resistance is already setup! Are you sure you want to reinitialize setup?", assume_yes, stdout, stdin);
+    if !response_yes {
+        println!("Canceled");
+        process::exit(1);
     }
+    true
 }
 
-fn cmd_setup_prompt_identity(stdout: &mut StdoutLock, stdin: &mut StdinLock) -> email::Identity {
-    let mut email_name = String::new();
-    print!("Enter a human-readable name to show to recipients when sending emails: ");
-    stdout.flush().unwrap();
-    stdin.read_line(&mut email_name).unwrap_or_else(|e| {
-        eprintln!("Failed to read from standard input: {}", e);
+fn cmd_setup_delete_config(cp: &mut CivilProtection) {
+    cp.delete_config().unwrap_or_else(|e| {
+        print_error!("Failed to delete existing config: {}", e);
         process::exit(1);
     });
-    email_name = email_name.trim_end().to_string();
+}
 
-    let mut email_address = String::new();
-    print!("Enter the email address to send emails from: ");
+fn prompt_line(prompt: &str, flag: &str, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> String {
+    if !stdin.is_terminal() {
+        print_error!("Standard input is not a terminal, pass `{}` to run setup non-interactively", flag);
+        process::exit(1);
+    }
+
+    let mut buf = String::new();
+    print!("{}", prompt);
     stdout.flush().unwrap();
-    stdin.read_line(&mut email_address).unwrap_or_else(|e| {
-        eprintln!("Failed to read from standard input: {}", e);
+    let bytes_read = stdin.read_line(&mut buf).unwrap_or_else(|e| {
+        print_error!("Failed to read from standard input: {}", e);
         process::exit(1);
     });
-    email_address = email_address.trim_end().to_string();
+    if bytes_read == 0 {
+        println!();
+        print_error!("Reached the end of standard input, canceled");
+        process::exit(1);
+    }
+    buf.trim_end().to_string()
+}
+
+fn cmd_setup_prompt_identity(args: &SquadmateSetupArgs, stdout: &mut StdoutLock, stdin: &mut StdinLock) -> email::Identity {
+    let email_name = match &args.name {
+        Some(name) => name.clone(),
+        None => prompt_line(
+            "Enter a human-readable name to show to recipients when sending emails: ",
+            "--name",
+            stdout,
+            stdin,
+        ),
+    };
+
+    let email_address = match &args.from {
+        Some(from) => from.clone(),
+        None => loop {
+            let email_address = prompt_line("Enter the email address to send emails from: ", "--from", stdout, stdin);
+            match parse_email(email_address.as_str()) {
+                Ok(email_address) => break email_address,
+                Err(e) => print_error!("Invalid from address: {}", e),
+            }
+        },
+    };
 
     email::Identity {
         name: email_name,
@@ -142,37 +412,90 @@ fn cmd_setup_prompt_identity(stdout: &mut StdoutLock, stdin: &mut StdinLock) ->
     }
 }
 
+fn cmd_setup_check_from_domain(identity: &email::Identity) {
+    let allowed_domains: Vec<&str> = ALLOWED_FROM_DOMAINS.unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    if allowed_domains.is_empty() {
+        return;
+    }
+
+    let domain = identity.email.rsplit_once('@').map(|(_, domain)| domain).unwrap_or("");
+    if !allowed_domains.iter().any(|allowed| allowed.eq_ignore_ascii_case(domain)) {
+        print_error!("Emails may only be sent from the following domains: {}", allowed_domains.join(", "));
+        process::exit(1);
+    }
+}
+
 fn cmd_setup_confirm_config(cp: &mut CivilProtection, create_config_result: Result<(), Box<dyn std::error::Error>>) {
     create_config_result.unwrap_or_else(|e| {
-        eprintln!("Failed to setup Resistance: {}", e);
+        print_error!("Failed to setup Resistance: {}", e);
         process::exit(1);
     });
 
     println!("Logging in...");
     cp.login().unwrap_or_else(|e| {
-        eprintln!("Failed to login: {}", e);
+        print_error!("Failed to login: {}", e);
         process::exit(1);
     });
 
-    println!("Resistance has been successfully setup")
+    print_success!("Resistance has been successfully setup")
 }
 
-fn cmd_setup_smtp(cp: &mut CivilProtection) {
+fn read_password(reader: &mut impl Read) -> io::Result<String> {
+    let mut password = String::new();
+    reader.read_to_string(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads the email password from `--password-fd`, `$CMACM_EMAIL_PASSWORD`, a systemd credential,
+/// or an interactive prompt, in that order
+fn cmd_setup_read_password(args: &SquadmateSetupArgs) -> io::Result<String> {
+    if let Some(fd) = args.password_fd {
+        // Opening it through /dev/fd gives us our own descriptor, leaving the inherited one untouched
+        return read_password(&mut File::open(format!("/dev/fd/{}", fd))?);
+    }
+
+    if let Ok(password) = env::var(PASSWORD_ENV_VAR) {
+        return Ok(password);
+    }
+
+    if let Some(credentials_dir) = env::var_os("CREDENTIALS_DIRECTORY") {
+        let credential_path = Path::new(&credentials_dir).join(PASSWORD_CREDENTIAL_NAME);
+        if credential_path.is_file() {
+            return read_password(&mut File::open(credential_path)?);
+        }
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("standard input is not a terminal, set {} or pass `--password-fd`", PASSWORD_ENV_VAR),
+        ));
+    }
+    rpassword::prompt_password("Enter the password for the email address given above: ")
+}
+
+fn cmd_setup_smtp(cp: &mut CivilProtection, args: &SquadmateSetupArgs) {
     let mut stdout = io::stdout().lock();
     let mut stdin = io::stdin().lock();
 
-    cmd_setup_check(cp, &mut stdout, &mut stdin);
+    let replace_config = cmd_setup_check(cp, args.yes, &mut stdout, &mut stdin);
 
-    let identity = cmd_setup_prompt_identity(&mut stdout, &mut stdin);
+    let identity = cmd_setup_prompt_identity(args, &mut stdout, &mut stdin);
+    cmd_setup_check_from_domain(&identity);
 
-    let email_password = rpassword::prompt_password(
-        "Enter the password for the email address given above: ",
-    )
-    .unwrap_or_else(|e| {
-        eprintln!("Failed to read password: {}", e);
+    let email_password = cmd_setup_read_password(args).unwrap_or_else(|e| {
+        print_error!("Failed to read password: {}", e);
         process::exit(1);
     });
 
+    if replace_config {
+        cmd_setup_delete_config(cp);
+    }
+
     let result = cp.create_config_smtp(
         identity,
         email_password,
@@ -181,60 +504,193 @@ fn cmd_setup_smtp(cp: &mut CivilProtection) {
     cmd_setup_confirm_config(cp, result);
 }
 
-fn cmd_setup_sendmail(cp: &mut CivilProtection) {
+fn cmd_setup_sendmail(cp: &mut CivilProtection, args: &SquadmateSetupArgs) {
+    if find_sendmail().is_none() {
+        print_error!("No executable `sendmail` found on PATH");
+        println!("\tInstall an MTA that provides `sendmail` (e.g. postfix or msmtp-mta), or use `setup smtp` instead");
+        process::exit(1);
+    }
+
     let mut stdout = io::stdout().lock();
     let mut stdin = io::stdin().lock();
 
-    cmd_setup_check(cp, &mut stdout, &mut stdin);
+    let replace_config = cmd_setup_check(cp, args.yes, &mut stdout, &mut stdin);
+
+    let identity = cmd_setup_prompt_identity(args, &mut stdout, &mut stdin);
+    cmd_setup_check_from_domain(&identity);
+
+    if replace_config {
+        cmd_setup_delete_config(cp);
+    }
 
-    let identity = cmd_setup_prompt_identity(&mut stdout, &mut stdin);
     let result = cp.create_config_sendmail(identity);
     cmd_setup_confirm_config(cp, result);
 }
 
 fn cmd_setup(cp: &mut CivilProtection, args: &SquadmateSetupArgs) {
+    if args.password_fd.is_some() && args.email_method != Some(SquadmateSetupEmailMethod::Smtp) {
+        print_error!("`--password-fd` is only used by `setup smtp`");
+        process::exit(1);
+    }
+
     match args.email_method {
         Some(email_method) => {
             match email_method {
-                SquadmateSetupEmailMethod::Smtp => cmd_setup_smtp(cp),
-                SquadmateSetupEmailMethod::Sendmail => cmd_setup_sendmail(cp),
+                SquadmateSetupEmailMethod::Smtp => cmd_setup_smtp(cp, args),
+                SquadmateSetupEmailMethod::Sendmail => cmd_setup_sendmail(cp, args),
             }
         }
-        None => {
-            let conf = cp.config().unwrap_or_else(|_| {
-                eprintln!("Not configured yet! Run with `--help` to show setup commands");
-                process::exit(1);
-            });
-
-            println!("Transport: {}", conf.email_setting);
-            println!("From Address: {}", conf.email);
-            if conf.squadmates.is_empty() {
-                println!("No squadmates! Add some with `cmacm add \"John Doe\" johndoe@example.com`");
+        None => cmd_list(cp, &SquadmateListArgs::default()),
+    }
+}
+
+/// Returns the `start..end` range of the page of `total` items selected by `offset` and `limit`
+fn page_range(total: usize, offset: usize, limit: Option<usize>) -> Range<usize> {
+    let start = offset.min(total);
+    let end = match limit {
+        Some(limit) => start.saturating_add(limit).min(total),
+        None => total,
+    };
+    start..end
+}
+
+fn cmd_list_json(cp: &mut CivilProtection, args: &SquadmateListArgs) {
+    let conf = cp.config().unwrap_or_else(|e| {
+        println!("{}", json!({ "error": format!("Failed to read config: {}", e) }));
+        process::exit(1);
+    });
+
+    let page = page_range(conf.squadmates.len(), args.offset, args.limit);
+    let squadmates: Vec<serde_json::Value> = conf.squadmates[page].iter().map(identity_json).collect();
+    println!("{}", json!({
+        "transport": conf.email_setting.to_string().to_lowercase(),
+        "from": conf.email.to_string(),
+        "total": conf.squadmates.len(),
+        "offset": args.offset,
+        "limit": args.limit,
+        "squadmates": squadmates,
+    }));
+
+    if conf.squadmates.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn cmd_list(cp: &mut CivilProtection, args: &SquadmateListArgs) {
+    if args.format == SquadmateListFormat::Json {
+        return cmd_list_json(cp, args);
+    }
+
+    let conf = cp.config().unwrap_or_else(|_| {
+        print_error!("Not configured yet! Run with `--help` to show setup commands");
+        process::exit(1);
+    });
+
+    let page = page_range(conf.squadmates.len(), args.offset, args.limit);
+    if args.email_only || args.name_only {
+        for squadmate in &conf.squadmates[page] {
+            if args.email_only {
+                println!("{}", squadmate.email);
             } else {
-                println!("Squadmates:");
-                for squadmate in conf.squadmates {
-                    println!("\t{}", squadmate);
-                }
+                println!("{}", squadmate.name);
             }
-        },
+        }
+        return;
+    }
+
+    println!("Transport: {}", conf.email_setting);
+    println!("From Address: {}", conf.email);
+    if conf.squadmates.is_empty() {
+        print_warning!("No squadmates! Add some with `cmacm add \"John Doe\" johndoe@example.com`");
+    } else {
+        println!("Squadmates:");
+        for squadmate in &conf.squadmates[page.clone()] {
+            println!("\t{}", squadmate);
+        }
+
+        if args.limit.is_some() || args.offset > 0 {
+            if page.is_empty() {
+                println!("Showing none of {}", conf.squadmates.len());
+            } else {
+                println!("Showing {}-{} of {}", page.start + 1, page.end, conf.squadmates.len());
+            }
+        }
     }
 }
 
+/// A deliberately loose address check that catches typos like a missing `@`, a missing domain, or stray whitespace
+///
+/// Domains must be ASCII, since internationalized domains aren't converted to punycode when sending.
+fn is_valid_email(email: &str) -> bool {
+    if email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    if local.is_empty() || local.contains('@') || local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return false;
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    labels.len() >= 2 && labels.iter().all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+fn parse_email(value: &str) -> Result<String, String> {
+    if !is_valid_email(value) {
+        return Err(format!("{:?} is not a valid email address", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Parses an identity in the form `Name <email>`
+fn parse_identity(value: &str) -> Result<email::Identity, String> {
+    let value = value.trim();
+    let (name, rest) = value.rsplit_once('<')
+        .ok_or_else(|| "expected the form `Name <email>`".to_string())?;
+    let email = rest.strip_suffix('>')
+        .ok_or_else(|| "missing closing `>` after the email address".to_string())?
+        .trim();
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err("missing a name before `<`".to_string());
+    }
+    Ok(email::Identity {
+        name: name.to_string(),
+        email: parse_email(email)?,
+    })
+}
+
 fn cmd_add(cp: &mut CivilProtection, args: &SquadmateAddArgs) {
     check_config(&cp);
 
-    let squadmate = email::Identity {
-        name: args.name.clone(),
-        email: args.email.clone(),
+    let squadmate = match &args.email {
+        Some(email) => email::Identity {
+            name: args.name.clone(),
+            email: email.clone(),
+        },
+        None => parse_identity(args.name.as_str()).unwrap_or_else(|e| {
+            print_error!("Invalid squadmate {:?}: {}", args.name, e);
+            eprintln!("Use either `cmacm add \"John Doe\" johndoe@example.com` or `cmacm add \"John Doe <johndoe@example.com>\"`");
+            process::exit(1);
+        }),
     };
 
     cp.add_squadmate(squadmate.clone())
         .unwrap_or_else(|e| {
-            eprintln!("Failed to add squadmate: {}", e);
+            print_error!("Failed to add squadmate: {}", e);
             process::exit(1);
         });
 
-    println!("Successfully added squadmate: {}", squadmate);
+    print_success!("Successfully added squadmate: {}", squadmate);
 }
 
 fn cmd_remove(cp: &mut CivilProtection, args: &SquadmateRmArgs) {
@@ -244,22 +700,22 @@ fn cmd_remove(cp: &mut CivilProtection, args: &SquadmateRmArgs) {
         SquadmateRmFieldType::Name => {
             cp.find_squadmate_by_name(args.value.as_str())
                 .unwrap_or_else(|e| {
-                    eprintln!("Error trying to find squadmate with name {}: {}", args.value, e);
+                    print_error!("Error trying to find squadmate with name {}: {}", args.value, e);
                     process::exit(1);
                 })
                 .unwrap_or_else(|| {
-                    eprintln!("Unable to find squadmate with name {}", args.value);
+                    print_error!("Unable to find squadmate with name {}", args.value);
                     process::exit(1);
                 })
         },
         SquadmateRmFieldType::Email => {
             cp.find_squadmate_by_email(args.value.as_str())
                 .unwrap_or_else(|e| {
-                    eprintln!("Error trying to find squadmate with email {}: {}", args.value, e);
+                    print_error!("Error trying to find squadmate with email {}: {}", args.value, e);
                     process::exit(1);
                 })
                 .unwrap_or_else(|| {
-                    eprintln!("Unable to find squadmate with email {}", args.value);
+                    print_error!("Unable to find squadmate with email {}", args.value);
                     process::exit(1);
                 })
         },
@@ -270,36 +726,692 @@ fn cmd_remove(cp: &mut CivilProtection, args: &SquadmateRmArgs) {
 
     let response_yes = prompt_yn(
         format!("Found squadmate {}, are you sure you want to remove them?", squadmate).as_str(),
+        args.yes,
         &mut stdout,
         &mut stdin,
     );
 
     if response_yes {
         cp.rm_squadmate(&squadmate).unwrap_or_else(|e| {
-            eprintln!("Failed to remove squadmate: {}", e);
+            print_error!("Failed to remove squadmate: {}", e);
             process::exit(1);
         });
 
-        println!("Successfully removed squadmate {}", squadmate);
+        print_success!("Successfully removed squadmate {}", squadmate);
     } else {
         println!("Canceled");
     }
 }
 
-fn cmd_test(cp: &mut CivilProtection) {
+fn is_csv_header(record: &csv::StringRecord) -> bool {
+    record.len() == 2
+        && record[0].eq_ignore_ascii_case("name")
+        && record[1].eq_ignore_ascii_case("email")
+}
+
+fn parse_csv_record(record: &csv::StringRecord) -> Result<email::Identity, String> {
+    if record.len() != 2 {
+        return Err(format!("expected 2 fields (name,email), found {}", record.len()));
+    }
+    if record[0].is_empty() {
+        return Err("missing a name".to_string());
+    }
+
+    Ok(email::Identity {
+        name: record[0].to_string(),
+        email: parse_email(&record[1])?,
+    })
+}
+
+/// Parses `name,email` rows, skipping an optional header row, into each row's line number and squadmate
+fn parse_squadmates_csv(input: impl Read) -> Vec<(u64, Result<email::Identity, String>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+
+    let mut rows = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let line = match &record {
+            Ok(record) => record.position().map(|position| position.line()),
+            Err(e) => e.position().map(|position| position.line()),
+        }
+        .unwrap_or(index as u64 + 1);
+
+        match record {
+            Ok(record) if index == 0 && is_csv_header(&record) => {},
+            Ok(record) => rows.push((line, parse_csv_record(&record))),
+            Err(e) => rows.push((line, Err(e.to_string()))),
+        }
+    }
+    rows
+}
+
+/// Undoes vCard text escaping (`\\`, `\,`, `\;` and `\n`), turning escaped newlines into spaces
+fn unescape_vcard_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push(' '),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn escape_vcard_text(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+}
+
+/// A vCard being read, from its `BEGIN:VCARD` line up to `END:VCARD`
+struct VcardEntry {
+    line: u64,
+    name: Option<String>,
+    emails: Vec<String>,
+}
+
+impl VcardEntry {
+    /// Turns the card into a squadmate, using the first valid email. `Ok(None)` means it has no usable email.
+    fn finish(self) -> Result<Option<email::Identity>, String> {
+        let email = match self.emails.iter().find_map(|email| parse_email(email).ok()) {
+            Some(email) => email,
+            None => return Ok(None),
+        };
+        let name = match self.name {
+            Some(name) if !name.is_empty() => name,
+            _ => return Err("missing a name (FN)".to_string()),
+        };
+        Ok(Some(email::Identity { name, email }))
+    }
+}
+
+/// Parses one or more vCards into each card's starting line number and squadmate. Cards without a usable
+/// email are returned separately, by line number, since they're skipped rather than treated as errors.
+fn parse_squadmates_vcard(input: impl Read) -> (Vec<(u64, Result<email::Identity, String>)>, Vec<u64>) {
+    // Unfold continuation lines, which start with a space or tab, onto the line they continue
+    let mut lines: Vec<(u64, String)> = Vec::new();
+    let mut rows = Vec::new();
+    for (index, line) in BufReader::new(input).lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                rows.push((line_number, Err(e.to_string())));
+                break;
+            },
+        };
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ => lines.push((line_number, line)),
+        }
+    }
+
+    let mut skipped = Vec::new();
+    let mut entry: Option<VcardEntry> = None;
+    for (line_number, line) in lines {
+        let (property, value) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        // Drop parameters (`EMAIL;TYPE=work`) and groups (`item1.EMAIL`)
+        let property = property.split(';').next().unwrap_or_default();
+        let property = property.rsplit('.').next().unwrap_or_default().to_ascii_uppercase();
+        let value = value.trim();
+
+        match property.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                if let Some(unfinished) = entry.take() {
+                    rows.push((unfinished.line, Err("missing END:VCARD".to_string())));
+                }
+                entry = Some(VcardEntry { line: line_number, name: None, emails: Vec::new() });
+            },
+            "END" if value.eq_ignore_ascii_case("VCARD") => {
+                if let Some(finished) = entry.take() {
+                    let line = finished.line;
+                    match finished.finish() {
+                        Ok(Some(squadmate)) => rows.push((line, Ok(squadmate))),
+                        Ok(None) => skipped.push(line),
+                        Err(e) => rows.push((line, Err(e))),
+                    }
+                }
+            },
+            "FN" => {
+                if let Some(card) = &mut entry {
+                    card.name = Some(unescape_vcard_text(value).trim().to_string());
+                }
+            },
+            "EMAIL" => {
+                if let Some(card) = &mut entry {
+                    card.emails.push(value.to_string());
+                }
+            },
+            _ => {},
+        }
+    }
+    if let Some(unfinished) = entry {
+        rows.push((unfinished.line, Err("missing END:VCARD".to_string())));
+    }
+
+    (rows, skipped)
+}
+
+/// The changes an import makes, worked out before anything is written
+struct ImportPlan {
+    remove: Vec<email::Identity>,
+    add: Vec<email::Identity>,
+    skip: Vec<email::Identity>,
+}
+
+/// Appends `rows` to `existing`, skipping any whose email is already present. With `replace`, every
+/// existing squadmate is removed first, so only duplicates within `rows` themselves are skipped.
+fn plan_import(existing: &[email::Identity], rows: Vec<email::Identity>, replace: bool) -> ImportPlan {
+    let mut plan = ImportPlan {
+        remove: if replace { existing.to_vec() } else { Vec::new() },
+        add: Vec::new(),
+        skip: Vec::new(),
+    };
+
+    let kept: &[email::Identity] = if replace { &[] } else { existing };
+    for squadmate in rows {
+        let is_duplicate = kept.iter()
+            .chain(plan.add.iter())
+            .any(|other| other.email.eq_ignore_ascii_case(&squadmate.email));
+        if is_duplicate {
+            plan.skip.push(squadmate);
+        } else {
+            plan.add.push(squadmate);
+        }
+    }
+    plan
+}
+
+/// Lists a plan's changes as `Name <email>`, one squadmate per line, prefixed with `-` for removals,
+/// `+` for additions and `=` for rows skipped as duplicates
+fn format_import_diff(plan: &ImportPlan) -> Vec<String> {
+    let line = |prefix: &str, squadmate: &email::Identity| format!("{} {} <{}>", prefix, squadmate.name, squadmate.email);
+    plan.remove.iter().map(|squadmate| line("-", squadmate))
+        .chain(plan.add.iter().map(|squadmate| line("+", squadmate)))
+        .chain(plan.skip.iter().map(|squadmate| line("=", squadmate)))
+        .collect()
+}
+
+fn cmd_import(cp: &mut CivilProtection, args: &SquadmateImportArgs) {
+    check_config(cp);
+
+    let file = File::open(&args.path).unwrap_or_else(|e| {
+        print_error!("Failed to open {}: {}", args.path.display(), e);
+        process::exit(1);
+    });
+
+    let parsed = match args.format {
+        SquadmateFileFormat::Csv => parse_squadmates_csv(file),
+        SquadmateFileFormat::Vcard => {
+            let (parsed, skipped) = parse_squadmates_vcard(file);
+            for line in &skipped {
+                print_warning!("Line {}: skipped a vCard with no usable email", line);
+            }
+            parsed
+        },
+    };
+
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (line, squadmate) in parsed {
+        match squadmate {
+            Ok(squadmate) => rows.push(squadmate),
+            Err(e) => {
+                print_error!("Line {}: {}", line, e);
+                failed += 1;
+            },
+        }
+    }
+    if args.replace && failed > 0 {
+        print_error!("Refusing to replace squadmates while {} rows could not be read, nothing was changed", failed);
+        process::exit(1);
+    }
+
+    let conf = cp.config().unwrap_or_else(|e| {
+        print_error!("Failed to read config: {}", e);
+        process::exit(1);
+    });
+    let plan = plan_import(&conf.squadmates, rows, args.replace);
+
+    if args.diff {
+        for line in format_import_diff(&plan) {
+            println!("{}", line);
+        }
+        if plan.remove.is_empty() && plan.add.is_empty() {
+            println!("Nothing to change");
+            return;
+        }
+    }
+
+    if args.diff || !plan.remove.is_empty() {
+        let prompt = if plan.remove.is_empty() {
+            format!("Add {} squadmates?", plan.add.len())
+        } else {
+            format!("This will remove all {} existing squadmates before importing, are you sure?", plan.remove.len())
+        };
+        let mut stdout = io::stdout().lock();
+        let mut stdin = io::stdin().lock();
+        let response_yes = prompt_yn(prompt.as_str(), args.yes, &mut stdout, &mut stdin);
+        if !response_yes {
+            println!("Canceled");
+            process::exit(1);
+        }
+    }
+
+    for squadmate in &plan.remove {
+        cp.rm_squadmate(squadmate).unwrap_or_else(|e| {
+            print_error!("Failed to remove squadmate {}: {}", squadmate, e);
+            process::exit(1);
+        });
+        PROGRESS.removed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    PROGRESS.skipped.store(plan.skip.len(), Ordering::SeqCst);
+    let mut added = 0;
+    for squadmate in plan.add {
+        let description = squadmate.to_string();
+        match cp.add_squadmate(squadmate) {
+            Ok(_) => {
+                added += 1;
+                PROGRESS.added.fetch_add(1, Ordering::SeqCst);
+            },
+            Err(e) => {
+                print_error!("Failed to add squadmate {}: {}", description, e);
+                failed += 1;
+            },
+        }
+    }
+
+    if !plan.remove.is_empty() {
+        println!("Removed {} squadmates", plan.remove.len());
+    }
+    println!("Added {} squadmates, skipped {} already present", added, plan.skip.len());
+    if failed > 0 {
+        print_error!("{} rows could not be imported", failed);
+        process::exit(1);
+    }
+}
+
+fn write_squadmates_csv(path: &Path, squadmates: &[email::Identity]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["name", "email"])?;
+    for squadmate in squadmates {
+        writer.write_record([squadmate.name.as_str(), squadmate.email.as_str()])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_squadmates_vcard(mut output: impl Write, squadmates: &[email::Identity]) -> io::Result<()> {
+    for squadmate in squadmates {
+        write!(output, "BEGIN:VCARD\r\n")?;
+        write!(output, "VERSION:4.0\r\n")?;
+        write!(output, "FN:{}\r\n", escape_vcard_text(&squadmate.name))?;
+        write!(output, "EMAIL:{}\r\n", squadmate.email)?;
+        write!(output, "END:VCARD\r\n")?;
+    }
+    output.flush()
+}
+
+fn cmd_export(cp: &mut CivilProtection, args: &SquadmateExportArgs) {
+    let conf = cp.config().unwrap_or_else(|_| {
+        print_error!("Not configured yet! Run with `--help` to show setup commands");
+        process::exit(1);
+    });
+
+    let result = match args.format {
+        SquadmateFileFormat::Csv => write_squadmates_csv(&args.path, &conf.squadmates).map_err(|e| e.to_string()),
+        SquadmateFileFormat::Vcard => File::create(&args.path)
+            .and_then(|file| write_squadmates_vcard(BufWriter::new(file), &conf.squadmates))
+            .map_err(|e| e.to_string()),
+    };
+    result.unwrap_or_else(|e| {
+        print_error!("Failed to write {}: {}", args.path.display(), e);
+        process::exit(1);
+    });
+
+    print_success!("Exported {} squadmates to {}", conf.squadmates.len(), args.path.display());
+}
+
+fn identity_json(identity: &email::Identity) -> serde_json::Value {
+    json!({
+        "name": identity.name,
+        "email": identity.email,
+    })
+}
+
+fn cmd_test_check_recipient_cap(cp: &mut CivilProtection, args: &SquadmateTestArgs) -> Result<(), String> {
+    let max_recipients = match args.max_recipients {
+        Some(max_recipients) if !args.force => max_recipients,
+        _ => return Ok(()),
+    };
+
+    let conf = cp.config().map_err(|e| format!("Failed to read config: {}", e))?;
+    if conf.squadmates.len() > max_recipients {
+        return Err(format!(
+            "Refusing to notify {} squadmates, which exceeds the maximum of {}. Use `--force` to send anyway",
+            conf.squadmates.len(),
+            max_recipients,
+        ));
+    }
+    Ok(())
+}
+
+fn cmd_test_json(cp: &mut CivilProtection, args: &SquadmateTestArgs) {
+    if let Err(e) = cmd_test_check_recipient_cap(cp, args) {
+        println!("{}", json!({ "error": e }));
+        process::exit(1);
+    }
+
+    let conf = cp.config().unwrap_or_else(|e| {
+        println!("{}", json!({ "error": format!("Failed to read config: {}", e) }));
+        process::exit(1);
+    });
+    let recipients: Vec<serde_json::Value> = conf.squadmates.iter().map(identity_json).collect();
+
+    if args.dry_run {
+        if let Err(e) = cp.login() {
+            println!("{}", json!({
+                "error": format!("Failed to login: {}", e),
+                "recipients": recipients,
+            }));
+            process::exit(1);
+        }
+
+        println!("{}", json!({
+            "dry_run": true,
+            "sent": 0,
+            "failed": [],
+            "recipients": recipients,
+        }));
+        return;
+    }
+
+    if let Err(e) = cp.notify_squadmates() {
+        println!("{}", json!({
+            "error": e.to_string(),
+            "recipients": recipients,
+        }));
+        process::exit(1);
+    }
+
+    println!("{}", json!({
+        "sent": recipients.len(),
+        "failed": [],
+        "recipients": recipients,
+    }));
+}
+
+fn cmd_test_explain(cp: &mut CivilProtection, args: &SquadmateTestArgs) {
+    let conf = cp.config().unwrap_or_else(|_| {
+        print_error!("Not configured yet! Run with `--help` to show setup commands");
+        process::exit(1);
+    });
+
+    println!("A test run would send one email to each squadmate:");
+    println!("\tTransport: {}", conf.email_setting);
+    println!("\tFrom Address: {}", conf.email);
+    match args.max_recipients {
+        Some(max_recipients) if args.force => println!("\tRecipient cap: {} (overridden by `--force`)", max_recipients),
+        Some(max_recipients) => println!("\tRecipient cap: {}", max_recipients),
+        None => println!("\tRecipient cap: none"),
+    }
+
+    if conf.squadmates.is_empty() {
+        print_warning!("No squadmates! Nobody would be notified");
+        return;
+    }
+
+    println!("\tRecipients ({}):", conf.squadmates.len());
+    for squadmate in &conf.squadmates {
+        println!("\t\t{}", squadmate);
+    }
+
+    if let Err(e) = cmd_test_check_recipient_cap(cp, args) {
+        print_warning!("{}", e);
+    }
+    println!("Nothing was sent.");
+}
+
+fn cmd_test_dry_run(cp: &mut CivilProtection) {
+    let conf = cp.config().unwrap_or_else(|_| {
+        print_error!("Not configured yet! Run with `--help` to show setup commands");
+        process::exit(1);
+    });
+
+    println!("Logging in...");
+    cp.login().unwrap_or_else(|e| {
+        print_error!("Failed to login: {}", e);
+        process::exit(1);
+    });
+
+    if conf.squadmates.is_empty() {
+        print_warning!("No squadmates! Nobody would be notified");
+        return;
+    }
+    for squadmate in &conf.squadmates {
+        println!("Would send to: {}", squadmate);
+    }
+    print_success!("Dry run complete, no emails were sent.");
+}
+
+fn cmd_test(cp: &mut CivilProtection, args: &SquadmateTestArgs) {
+    if args.explain {
+        return cmd_test_explain(cp, args);
+    }
+    if args.json {
+        return cmd_test_json(cp, args);
+    }
+
+    cmd_test_check_recipient_cap(cp, args).unwrap_or_else(|e| {
+        print_error!("{}", e);
+        process::exit(1);
+    });
+
+    if args.dry_run {
+        return cmd_test_dry_run(cp);
+    }
+
     cp.notify_squadmates().unwrap_or_else(|e| {
-        eprintln!("Failed to send email! Is Resistance setup correctly?");
+        print_error!("Failed to send email! Is Resistance setup correctly?");
         eprintln!("{}", e);
         process::exit(1);
     });
 
-    println!("Sent a test email to all Squadmates. Confirm with them that they received the email.");
+    print_success!("Sent a test email to all Squadmates. Confirm with them that they received the email.");
 }
 
-fn main() {
-    setup_logging();
+/// The checks run by `doctor`, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DoctorCheck {
+    ConfigExists,
+    ConfigParses,
+    Login,
+    FromAddress,
+    Squadmates,
+    RecipientCap,
+    Sendmail,
+}
 
-    let cli = Cli::parse();
+impl DoctorCheck {
+    /// Exit code used by `doctor --check` when this is the first check to fail
+    fn exit_code(self) -> i32 {
+        match self {
+            DoctorCheck::ConfigExists => 2,
+            DoctorCheck::ConfigParses => 3,
+            DoctorCheck::Login => 4,
+            DoctorCheck::FromAddress => 5,
+            DoctorCheck::Squadmates => 6,
+            DoctorCheck::RecipientCap => 7,
+            DoctorCheck::Sendmail => 8,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DoctorCheck::ConfigExists => "config_exists",
+            DoctorCheck::ConfigParses => "config_parses",
+            DoctorCheck::Login => "login",
+            DoctorCheck::FromAddress => "from_address",
+            DoctorCheck::Squadmates => "squadmates",
+            DoctorCheck::RecipientCap => "recipient_cap",
+            DoctorCheck::Sendmail => "sendmail",
+        }
+    }
+}
+
+struct DoctorReport<'a> {
+    args: &'a SquadmateDoctorArgs,
+    first_failure: Option<DoctorCheck>,
+}
+
+impl DoctorReport<'_> {
+    fn report(&mut self, check: DoctorCheck, passed: bool, description: &str, hint: &str) -> bool {
+        if !self.args.check {
+            if passed {
+                print_success!("{}", description);
+            } else {
+                print_failure!("{}", description);
+                println!("\t{}", hint);
+            }
+        }
+
+        if !passed && self.first_failure.is_none() {
+            self.first_failure = Some(check);
+        }
+        passed
+    }
+
+    fn finish(&self) -> ! {
+        if !self.args.check {
+            process::exit(if self.first_failure.is_some() { 1 } else { 0 });
+        }
+
+        let exit_code = self.first_failure.map(DoctorCheck::exit_code).unwrap_or(0);
+        if self.args.json {
+            println!("{}", json!({
+                "ok": self.first_failure.is_none(),
+                "failed_check": self.first_failure.map(DoctorCheck::name),
+                "exit_code": exit_code,
+            }));
+        }
+        process::exit(exit_code);
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Looks for an executable `sendmail` in the directories on `PATH`
+fn find_sendmail() -> Option<PathBuf> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .map(|dir| dir.join("sendmail"))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn cmd_doctor(cp: &mut CivilProtection, args: &SquadmateDoctorArgs) {
+    let mut report = DoctorReport { args, first_failure: None };
+
+    let config_exists = report.report(
+        DoctorCheck::ConfigExists,
+        cp.does_config_exist(),
+        "Config file exists",
+        "Run `cmacm setup smtp` or `cmacm setup sendmail` to create one",
+    );
+    if !config_exists {
+        report.finish();
+    }
+
+    let conf = match cp.config() {
+        Ok(conf) => {
+            report.report(DoctorCheck::ConfigParses, true, "Config file parses", "");
+            conf
+        },
+        Err(e) => {
+            report.report(
+                DoctorCheck::ConfigParses,
+                false,
+                "Config file parses",
+                format!("{}. Re-run setup to recreate it", e).as_str(),
+            );
+            report.finish();
+        },
+    };
+
+    let login_hint = match cp.login() {
+        Ok(_) => String::new(),
+        Err(e) => format!("{}. Check the credentials and transport given during setup", e),
+    };
+    report.report(DoctorCheck::Login, login_hint.is_empty(), "Logged in to the email transport", login_hint.as_str());
+
+    let from_address = conf.email.to_string();
+    report.report(
+        DoctorCheck::FromAddress,
+        is_valid_email(from_address.as_str()),
+        "From address is valid",
+        format!("{:?} is not a valid email address. Re-run setup with a correct address", from_address).as_str(),
+    );
+
+    report.report(
+        DoctorCheck::Squadmates,
+        !conf.squadmates.is_empty(),
+        "At least one squadmate is configured",
+        "Add one with `cmacm add \"John Doe\" johndoe@example.com`",
+    );
+
+    if let Some(max_recipients) = env::var_os(MAX_RECIPIENTS_ENV_VAR) {
+        let max_recipients = max_recipients.to_string_lossy();
+        match max_recipients.parse::<usize>() {
+            Ok(max_recipients) => report.report(
+                DoctorCheck::RecipientCap,
+                conf.squadmates.len() <= max_recipients,
+                format!(
+                    "Recipient cap is active: {} squadmates, at most {} allowed by {}",
+                    conf.squadmates.len(),
+                    max_recipients,
+                    MAX_RECIPIENTS_ENV_VAR,
+                ).as_str(),
+                "`cmacm test` will refuse to send without `--force`. Raise the cap or remove squadmates",
+            ),
+            Err(_) => report.report(
+                DoctorCheck::RecipientCap,
+                false,
+                format!("Recipient cap {}={:?} is valid", MAX_RECIPIENTS_ENV_VAR, max_recipients).as_str(),
+                "Set it to a whole number, or unset it to disable the cap",
+            ),
+        };
+    }
+
+    if conf.email_setting.to_string().to_lowercase().contains("sendmail") {
+        report.report(
+            DoctorCheck::Sendmail,
+            find_sendmail().is_some(),
+            "sendmail binary is on PATH",
+            "Install an MTA that provides `sendmail` (e.g. postfix or msmtp-mta) and make sure its directory is on PATH, or re-run setup with SMTP",
+        );
+    }
+
+    report.finish();
+}
+
+fn run_command(cli: &Cli) {
     let mut cp = CivilProtection::new();
 
     match &cli.squadmate_cmd {
@@ -308,10 +1420,251 @@ fn main() {
                 SquadmateCommands::Setup(args) => cmd_setup(&mut cp, args),
                 SquadmateCommands::Add(args) => cmd_add(&mut cp, args),
                 SquadmateCommands::Remove(args) => cmd_remove(&mut cp, args),
-                SquadmateCommands::Test => cmd_test(&mut cp),
+                SquadmateCommands::Test(args) => cmd_test(&mut cp, args),
+                SquadmateCommands::List(args) => cmd_list(&mut cp, args),
+                SquadmateCommands::Import(args) => cmd_import(&mut cp, args),
+                SquadmateCommands::Export(args) => cmd_export(&mut cp, args),
+                SquadmateCommands::Doctor(args) => cmd_doctor(&mut cp, args),
             }
         },
-        None => cmd_setup(&mut cp, &SquadmateSetupArgs { email_method: None })
+        None => cmd_setup(&mut cp, &SquadmateSetupArgs::default())
     }
+}
+
+fn run_command_with_deadline(cli: Cli, deadline: Duration) {
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        run_command(&cli);
+        let _ = done_tx.send(());
+    });
 
+    match done_rx.recv_timeout(deadline) {
+        Ok(()) => {},
+        Err(RecvTimeoutError::Timeout) => {
+            let deadline = humantime::format_duration(deadline);
+            log::error!("Aborting after exceeding the {} deadline", deadline);
+            print_error!("Timed out: command did not finish within {}", deadline);
+            if let Some(summary) = PROGRESS.summary() {
+                log::warn!("Completed before the deadline: {}", summary);
+                print_warning!("Completed before the deadline: {}", summary);
+            }
+            process::exit(1);
+        },
+        Err(RecvTimeoutError::Disconnected) => {
+            // The command thread panicked, which has already been reported
+            process::exit(1);
+        },
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.no_color {
+        owo_colors::set_override(false);
+    }
+    if !cli.no_syslog {
+        setup_logging();
+    }
+
+    match cli.deadline {
+        Some(deadline) => run_command_with_deadline(cli, deadline),
+        None => run_command(&cli),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_emails() {
+        assert!(is_valid_email("johndoe@example.com"));
+        assert!(is_valid_email("john.doe+alerts@mail.example.co.uk"));
+    }
+
+    #[test]
+    fn invalid_emails() {
+        assert!(!is_valid_email("johndoe@example"));
+        assert!(!is_valid_email("a b@x.com"));
+        assert!(!is_valid_email(".a@x.com"));
+        assert!(!is_valid_email("a.@x.com"));
+        assert!(!is_valid_email("a..b@x.com"));
+        assert!(!is_valid_email("a@-x.com"));
+        assert!(!is_valid_email("a@x-.com"));
+        assert!(!is_valid_email("a@x..com"));
+        // Not deliverable until the transport converts internationalized domains to punycode
+        assert!(!is_valid_email("user@münchen.de"));
+        assert!(!is_valid_email("johndoe"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("a@b@example.com"));
+    }
+
+    #[test]
+    fn page_range_clamps_to_total() {
+        assert_eq!(page_range(137, 0, None), 0..137);
+        assert_eq!(page_range(137, 20, Some(20)), 20..40);
+        assert_eq!(page_range(137, 130, Some(20)), 130..137);
+        assert_eq!(page_range(137, 200, Some(20)), 137..137);
+        assert_eq!(page_range(5, 1, Some(usize::MAX)), 1..5);
+    }
+
+    #[test]
+    fn csv_skips_header_row() {
+        let rows = parse_squadmates_csv("name,email\nJohn Doe,johndoe@example.com\n".as_bytes());
+        assert_eq!(rows.len(), 1);
+        let (line, squadmate) = &rows[0];
+        assert_eq!(*line, 2);
+        let squadmate = squadmate.as_ref().unwrap();
+        assert_eq!(squadmate.name, "John Doe");
+        assert_eq!(squadmate.email, "johndoe@example.com");
+    }
+
+    #[test]
+    fn csv_without_header_keeps_first_row() {
+        let rows = parse_squadmates_csv("John Doe,johndoe@example.com\n".as_bytes());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, 1);
+        assert!(rows[0].1.is_ok());
+    }
+
+    #[test]
+    fn csv_reports_malformed_rows_by_line() {
+        let input = "name,email\nJohn Doe,johndoe@example.com,extra\nJane Doe,janedoe@example\n,nobody@example.com\nJim Doe,jimdoe@example.com\n";
+        let rows = parse_squadmates_csv(input.as_bytes());
+        assert_eq!(rows.len(), 4);
+
+        assert_eq!(rows[0].0, 2);
+        assert!(rows[0].1.as_ref().err().unwrap().contains("expected 2 fields"));
+        assert_eq!(rows[1].0, 3);
+        assert!(rows[1].1.as_ref().err().unwrap().contains("not a valid email address"));
+        assert_eq!(rows[2].0, 4);
+        assert!(rows[2].1.as_ref().err().unwrap().contains("missing a name"));
+        assert_eq!(rows[3].0, 5);
+        assert!(rows[3].1.is_ok());
+    }
+
+    #[test]
+    fn csv_quoted_name_with_comma() {
+        let rows = parse_squadmates_csv("\"Doe, John\",johndoe@example.com\n".as_bytes());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].1.as_ref().unwrap().name, "Doe, John");
+    }
+
+    #[test]
+    fn vcard_reads_multiple_cards() {
+        let input = "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:John Doe\r\nEMAIL;TYPE=work:johndoe@example.com\r\nEND:VCARD\r\n\
+                     BEGIN:VCARD\r\nVERSION:3.0\r\nfn:Doe\\, Jane\r\nitem1.EMAIL:janedoe@example.com\r\nEND:VCARD\r\n";
+        let (rows, skipped) = parse_squadmates_vcard(input.as_bytes());
+        assert!(skipped.is_empty());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 1);
+        assert_eq!(rows[0].1.as_ref().unwrap().email, "johndoe@example.com");
+        assert_eq!(rows[1].0, 6);
+        assert_eq!(rows[1].1.as_ref().unwrap().name, "Doe, Jane");
+    }
+
+    #[test]
+    fn vcard_unfolds_continuation_lines() {
+        let input = "BEGIN:VCARD\nFN:John\n  Doe\nEMAIL:johndoe@exa\n mple.com\nEND:VCARD\n";
+        let (rows, _) = parse_squadmates_vcard(input.as_bytes());
+        let squadmate = rows[0].1.as_ref().unwrap();
+        assert_eq!(squadmate.name, "John Doe");
+        assert_eq!(squadmate.email, "johndoe@example.com");
+    }
+
+    #[test]
+    fn vcard_skips_cards_without_usable_email() {
+        let input = "BEGIN:VCARD\nFN:John Doe\nTEL:555-0100\nEND:VCARD\n\
+                     BEGIN:VCARD\nFN:Jane Doe\nEMAIL:not-an-email\nEND:VCARD\n\
+                     BEGIN:VCARD\nEMAIL:nobody@example.com\nEND:VCARD\n\
+                     BEGIN:VCARD\nFN:Unfinished\n";
+        let (rows, skipped) = parse_squadmates_vcard(input.as_bytes());
+        assert_eq!(skipped, vec![1, 5]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 9);
+        assert!(rows[0].1.is_err());
+        assert_eq!(rows[1].0, 12);
+        assert!(rows[1].1.is_err());
+    }
+
+    fn identity(name: &str, email: &str) -> email::Identity {
+        email::Identity {
+            name: name.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    #[test]
+    fn vcard_round_trips_through_export() {
+        let squadmates = vec![identity("Doe, John; Jr", "johndoe@example.com")];
+        let mut output = Vec::new();
+        write_squadmates_vcard(&mut output, &squadmates).unwrap();
+
+        let (rows, skipped) = parse_squadmates_vcard(output.as_slice());
+        assert!(skipped.is_empty());
+        assert_eq!(rows[0].1.as_ref().unwrap().name, "Doe, John; Jr");
+        assert_eq!(rows[0].1.as_ref().unwrap().email, "johndoe@example.com");
+    }
+
+    #[test]
+    fn import_appends_and_skips_existing() {
+        let existing = vec![identity("John Doe", "johndoe@example.com")];
+        let rows = vec![
+            identity("Johnny", "JohnDoe@example.com"),
+            identity("Jane Doe", "janedoe@example.com"),
+            identity("Jane Again", "janedoe@example.com"),
+        ];
+
+        let plan = plan_import(&existing, rows, false);
+        assert!(plan.remove.is_empty());
+        assert_eq!(plan.add.len(), 1);
+        assert_eq!(plan.add[0].name, "Jane Doe");
+        assert_eq!(plan.skip.len(), 2);
+    }
+
+    #[test]
+    fn import_replace_removes_existing_first() {
+        let existing = vec![identity("John Doe", "johndoe@example.com")];
+        let rows = vec![
+            identity("Johnny", "johndoe@example.com"),
+            identity("Johnny Again", "johndoe@example.com"),
+        ];
+
+        let plan = plan_import(&existing, rows, true);
+        assert_eq!(plan.remove.len(), 1);
+        assert_eq!(plan.add.len(), 1);
+        assert_eq!(plan.add[0].name, "Johnny");
+        assert_eq!(plan.skip.len(), 1);
+    }
+
+    #[test]
+    fn import_diff_lists_every_change() {
+        let existing = vec![identity("John Doe", "johndoe@example.com")];
+        let rows = vec![
+            identity("Jane Doe", "janedoe@example.com"),
+            identity("Jane Again", "janedoe@example.com"),
+        ];
+
+        let plan = plan_import(&existing, rows, true);
+        assert_eq!(format_import_diff(&plan), vec![
+            "- John Doe <johndoe@example.com>",
+            "+ Jane Doe <janedoe@example.com>",
+            "= Jane Again <janedoe@example.com>",
+        ]);
+    }
+
+    #[test]
+    fn parse_identity_splits_name_and_email() {
+        let identity = parse_identity("  John Doe <johndoe@example.com> ").unwrap();
+        assert_eq!(identity.name, "John Doe");
+        assert_eq!(identity.email, "johndoe@example.com");
+    }
+
+    #[test]
+    fn parse_identity_errors() {
+        assert!(parse_identity("John Doe johndoe@example.com").err().unwrap().contains("expected the form"));
+        assert!(parse_identity("John Doe <johndoe@example.com").err().unwrap().contains("missing closing `>`"));
+        assert!(parse_identity("<johndoe@example.com>").err().unwrap().contains("missing a name"));
+        assert!(parse_identity("John Doe <johndoe@example>").err().unwrap().contains("not a valid email address"));
+    }
 }